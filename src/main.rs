@@ -1,9 +1,16 @@
-use clap::Parser;
-use memmap2::{Advice, MmapOptions};
+use clap::{Parser, ValueEnum};
+use hdrhistogram::Histogram;
+use memmap2::{Advice, MmapMut, MmapOptions};
+use rand::seq::SliceRandom;
 use rand::Rng;
-use std::fs::File;
+use std::alloc::{alloc, dealloc, Layout};
+use std::fs::{File, OpenOptions};
 use std::io::{self, Read};
+use std::ops::{Deref, DerefMut};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 use std::thread;
 use std::time::{Duration, Instant};
 
@@ -21,6 +28,63 @@ struct Args {
 
     /// madvise hint (0: NORMAL, 1: RANDOM, 2: SEQUENTIAL)
     hint: usize,
+
+    /// Access method: touch pages through the mmap, or issue explicit pread(2) calls
+    #[arg(long, value_enum, default_value = "mmap")]
+    method: Method,
+
+    /// Open the file with O_DIRECT and read into block-aligned buffers,
+    /// bypassing the page cache (only meaningful with `--method pread`)
+    #[arg(long)]
+    direct: bool,
+
+    /// Read/write workload mode. `write` and `mixed` require `--method mmap`,
+    /// since they dirty the mapping with a MAP_SHARED store rather than
+    /// issuing pwrite(2) calls.
+    #[arg(long, value_enum, default_value = "read")]
+    rw: RwMode,
+
+    /// Fraction of accesses that are writes in `--rw mixed` mode
+    #[arg(long, default_value_t = 0.5)]
+    write_ratio: f64,
+
+    /// Advise the kernel to back the mapping with transparent huge pages
+    /// (Advice::HugePage), same as passing `hint` 3
+    #[arg(long)]
+    hugepage: bool,
+
+    /// Attempt an explicit MAP_HUGETLB mapping using huge pages of this size
+    /// in bytes (e.g. 2097152 for 2 MiB, 1073741824 for 1 GiB). Requires
+    /// huge pages to already be reserved on the system; falls back to a
+    /// regular mmap on failure.
+    #[arg(long)]
+    hugetlb: Option<u64>,
+
+    /// How worker threads account page touches: `shared` fetch_adds a
+    /// handful of global atomics every access (the historical behavior);
+    /// `sharded` gives each thread its own cache-line-padded counters,
+    /// summed by the monitoring loop once a second.
+    #[arg(long, value_enum, default_value = "shared")]
+    counter_mode: CounterMode,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Method {
+    Mmap,
+    Pread,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum RwMode {
+    Read,
+    Write,
+    Mixed,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum CounterMode {
+    Shared,
+    Sharded,
 }
 
 // Constants for byte units
@@ -28,39 +92,285 @@ const KB: u64 = 1024;
 const MB: u64 = 1024 * KB;
 const GB: u64 = 1024 * MB;
 const PAGE_SIZE: u64 = 4096;
-const SCAN_BLOCK_SIZE: u64 = 128 * MB;
+// Bounds, in PAGE_SIZE blocks, on how big a sequential-scan chunk can be.
+const MIN_CHUNK_BLOCKS: u64 = 128;
+const MAX_CHUNK_BLOCKS: u64 = 4096;
+// Target number of chunks per thread, used to size chunks relative to the
+// file so each thread gets many chunks spread across the whole device.
+const CHUNKS_PER_THREAD: u64 = 64;
+// Fallback O_DIRECT alignment used if querying the device's actual logical
+// block size (via BLKSSZGET) fails.
+const DIRECT_ALIGN_FALLBACK: u64 = 512;
+// How many writes a worker batches before issuing an msync for the page it
+// just dirtied.
+const MSYNC_EVERY: u64 = 1024;
+// Latency histogram range: 1ns to 10s, tracked to 3 significant digits.
+const HIST_MAX_NS: u64 = 10_000_000_000;
+const HIST_SIG_FIGS: u8 = 3;
+
+fn new_latency_histogram() -> Histogram<u64> {
+    let mut hist = Histogram::new_with_bounds(1, HIST_MAX_NS, HIST_SIG_FIGS).unwrap();
+    // Accesses occasionally stall well past our nominal bound (e.g. a page
+    // fault that blocks on IO); let the histogram grow rather than drop them.
+    hist.auto(true);
+    hist
+}
+
+/// Per-thread counters in `--counter-mode sharded`, padded to a cache line
+/// so adjacent threads' entries never bounce the same line between cores.
+#[repr(align(64))]
+#[derive(Default)]
+struct ThreadCounters {
+    counts: AtomicU64,
+    sums: AtomicU64,
+    writes: AtomicU64,
+}
+
+/// Owns the counters for the run: either the historical shared atomics, or
+/// one cache-line-padded `ThreadCounters` per worker thread.
+enum CounterStore {
+    Shared {
+        counts: AtomicU64,
+        sums: AtomicU64,
+        writes: AtomicU64,
+    },
+    Sharded(Vec<ThreadCounters>),
+}
+
+impl CounterStore {
+    fn new(mode: CounterMode, threads: usize) -> Self {
+        match mode {
+            CounterMode::Shared => CounterStore::Shared {
+                counts: AtomicU64::new(0),
+                sums: AtomicU64::new(0),
+                writes: AtomicU64::new(0),
+            },
+            CounterMode::Sharded => {
+                CounterStore::Sharded((0..threads).map(|_| ThreadCounters::default()).collect())
+            }
+        }
+    }
+
+    /// The counter sink a given worker thread should record through.
+    fn sink(&self, thread_idx: usize) -> Counters<'_> {
+        match self {
+            CounterStore::Shared {
+                counts,
+                sums,
+                writes,
+            } => Counters::Shared {
+                counts,
+                sums,
+                writes,
+            },
+            CounterStore::Sharded(slots) => Counters::Sharded(&slots[thread_idx]),
+        }
+    }
+
+    /// Sums and resets the per-interval totals the monitoring loop reports.
+    fn swap_totals(&self) -> (u64, u64) {
+        match self {
+            CounterStore::Shared { counts, writes, .. } => (
+                counts.swap(0, Ordering::Relaxed),
+                writes.swap(0, Ordering::Relaxed),
+            ),
+            CounterStore::Sharded(slots) => slots.iter().fold((0, 0), |(c, w), slot| {
+                (
+                    c + slot.counts.swap(0, Ordering::Relaxed),
+                    w + slot.writes.swap(0, Ordering::Relaxed),
+                )
+            }),
+        }
+    }
+}
+
+/// A worker thread's handle into the counter store: either shared global
+/// atomics, or its own padded slot in the sharded array.
+enum Counters<'a> {
+    Shared {
+        counts: &'a AtomicU64,
+        sums: &'a AtomicU64,
+        writes: &'a AtomicU64,
+    },
+    Sharded(&'a ThreadCounters),
+}
+
+impl Counters<'_> {
+    fn record(&self, is_write: bool, val: u64) {
+        let (counts, sums, writes) = match self {
+            Counters::Shared {
+                counts,
+                sums,
+                writes,
+            } => (*counts, *sums, *writes),
+            Counters::Sharded(slot) => (&slot.counts, &slot.sums, &slot.writes),
+        };
+        if is_write {
+            writes.fetch_add(1, Ordering::Relaxed);
+        }
+        sums.fetch_add(val, Ordering::Relaxed);
+        counts.fetch_add(1, Ordering::Relaxed);
+    }
+}
 
 fn main() -> io::Result<()> {
     let args = Args::parse();
 
-    let file = File::open(&args.dev)?;
+    // Write and mixed workloads dirty the mapping in place, which only makes
+    // sense for the mmap method; fall back to a read-only workload otherwise.
+    let mut rw = args.rw;
+    if matches!(args.method, Method::Pread) && rw != RwMode::Read {
+        eprintln!("--rw write/mixed requires --method mmap; forcing --rw read");
+        rw = RwMode::Read;
+    }
+
+    let needs_write_mapping = matches!(args.method, Method::Mmap) && rw != RwMode::Read;
+    let file = if needs_write_mapping {
+        OpenOptions::new().read(true).write(true).open(&args.dev)?
+    } else {
+        File::open(&args.dev)?
+    };
     let file_size = file.metadata()?.len();
     if file_size == 0 {
         eprintln!("File size is zero");
         return Ok(());
     }
 
-    // Memory-map the file
-    let mmap = unsafe { MmapOptions::new().len(file_size as usize).map(&file)? };
-
-    // Apply madvise hint using memmap2's advise method
+    // Only the mmap method needs the file mapped into our address space; the
+    // pread method reads through a dedicated file handle below. A writable
+    // workload maps the file MAP_SHARED via `map_mut` so stores land in the
+    // page cache and get written back by the kernel (or our own msync calls).
     let advice = match args.hint {
         1 => Advice::Random,
         2 => Advice::Sequential,
+        3 => Advice::HugePage,
         _ => Advice::Normal,
     };
-    mmap.advise(advice)?;
+    let wants_hugepage_advice = args.hugepage || args.hint == 3;
+
+    let mut mmap_rw: Option<MmapMut> = None;
+    let mut mmap_huge: Option<HugeTlbMap> = None;
+    let mmap = match args.method {
+        Method::Mmap if needs_write_mapping => {
+            if args.hugetlb.is_some() {
+                eprintln!("--hugetlb is not supported with --rw write/mixed; ignoring --hugetlb");
+            }
+            let m = unsafe { MmapOptions::new().len(file_size as usize).map_mut(&file)? };
+            m.advise(advice)?;
+            if wants_hugepage_advice {
+                m.advise(Advice::HugePage)?;
+            }
+            mmap_rw = Some(m);
+            None
+        }
+        Method::Mmap if args.hugetlb.is_some() => {
+            let huge_size = args.hugetlb.unwrap();
+            match HugeTlbMap::new(&file, file_size as usize, huge_size) {
+                Ok(m) => {
+                    mmap_huge = Some(m);
+                    None
+                }
+                Err(e) => {
+                    eprintln!("MAP_HUGETLB mapping failed ({e}), falling back to a regular mmap");
+                    let mmap = unsafe { MmapOptions::new().len(file_size as usize).map(&file)? };
+                    mmap.advise(advice)?;
+                    if wants_hugepage_advice {
+                        mmap.advise(Advice::HugePage)?;
+                    }
+                    Some(mmap)
+                }
+            }
+        }
+        Method::Mmap => {
+            let mmap = unsafe { MmapOptions::new().len(file_size as usize).map(&file)? };
+            mmap.advise(advice)?;
+            if wants_hugepage_advice {
+                mmap.advise(Advice::HugePage)?;
+            }
+            Some(mmap)
+        }
+        Method::Pread => None,
+    };
+    // Raw pointer into the writable mapping, shared read/write across worker
+    // threads without synchronization. Workers can store to the same byte
+    // offsets (random mode picks any offset, and wrapped sequential plans
+    // can share a chunk), so racing stores may overwrite each other; that
+    // is fine for a benchmark that cares about dirty-page behavior, not
+    // data integrity.
+    let mmap_rw_ptr = mmap_rw.as_mut().map(|m| RawMut(m.as_mut_ptr()));
+    // `--rw write|mixed` with `--method mmap` only ever populates `mmap_rw`
+    // (see `needs_write_mapping` above), so `read_one` needs a read view of
+    // it too -- otherwise Mixed mode's reads panic on `mmap.unwrap()`.
+    let mmap_ref: Option<&[u8]> = mmap
+        .as_deref()
+        .or(mmap_rw.as_deref())
+        .or(mmap_huge.as_deref());
+
+    // For the pread method, optionally reopen the file with O_DIRECT so reads
+    // bypass the page cache and hit the device directly. Fall back to a
+    // regular buffered open if O_DIRECT isn't supported on this filesystem.
+    let mut direct_active = false;
+    let mut direct_align = DIRECT_ALIGN_FALLBACK;
+    let pread_file = match args.method {
+        Method::Pread if args.direct => {
+            match OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_DIRECT)
+                .open(&args.dev)
+            {
+                Ok(f) => {
+                    direct_active = true;
+                    direct_align = query_logical_block_size(&f).unwrap_or(DIRECT_ALIGN_FALLBACK);
+                    Some(f)
+                }
+                Err(e) => {
+                    eprintln!("O_DIRECT open failed ({e}), falling back to buffered pread");
+                    Some(File::open(&args.dev)?)
+                }
+            }
+        }
+        Method::Pread => Some(File::open(&args.dev)?),
+        Method::Mmap => None,
+    };
 
-    // Shared atomic counters
-    let counts = AtomicU64::new(0);
-    let sums = AtomicU64::new(0);
+    // Page-touch accounting: either a handful of global atomics, or one
+    // cache-line-padded counter set per thread.
+    let counter_store = CounterStore::new(args.counter_mode, args.threads);
     let cpu_work = AtomicU64::new(0);
-    let seq_scan_pos = AtomicU64::new(0);
+
+    // One latency histogram slot per worker thread. Each worker accumulates
+    // into its own local histogram on the hot path (no lock) and only
+    // touches its slot's mutex once a second to hand the batch off for
+    // reporting.
+    let latency_hists: Vec<Mutex<Histogram<u64>>> = (0..args.threads)
+        .map(|_| Mutex::new(new_latency_histogram()))
+        .collect();
+
+    // Precompute the sequential scan's work plan instead of funneling every
+    // thread through one shared position counter: split the file into
+    // chunks, shuffle them, and deal them round-robin to threads. Shuffling
+    // spreads each thread's chunks across the whole device instead of
+    // letting stragglers cluster on the same region, and there's no shared
+    // atomic left to contend on.
+    let seq_plans = build_seq_scan_plans(file_size, args.threads);
 
     // Scoped threads using std::thread::scope
     thread::scope(|s| {
         // Spawn worker threads
-        spawn_worker_threads(s, &args, &counts, &sums, &seq_scan_pos, &mmap, file_size);
+        spawn_worker_threads(
+            s,
+            &args,
+            rw,
+            &counter_store,
+            seq_plans,
+            mmap_ref,
+            mmap_rw_ptr,
+            pread_file.as_ref(),
+            direct_active,
+            direct_align,
+            file_size,
+            &latency_hists,
+        );
 
         // CPU work thread
         {
@@ -77,7 +387,9 @@ fn main() -> io::Result<()> {
         }
 
         // Monitoring loop
-        println!("dev,seq,hint,threads,time,workGB,tlb,readGB,CPUwork");
+        println!(
+            "dev,seq,hint,threads,time,workGB,writeGB,tlb,readGB,CPUwork,p50,p99,p999,max,hugePages"
+        );
         let start = Instant::now();
         let mut last_shootdowns = read_tlb_shootdown_count().unwrap_or(0);
         let mut last_io_bytes = read_io_bytes().unwrap_or(0);
@@ -86,20 +398,39 @@ fn main() -> io::Result<()> {
             thread::sleep(Duration::from_secs(1));
             let shootdowns = read_tlb_shootdown_count().unwrap_or(0);
             let io_bytes = read_io_bytes().unwrap_or(0);
-            let work_count = counts.swap(0, Ordering::Relaxed);
+            let (work_count, write_count) = counter_store.swap_totals();
             let cpu_work_count = cpu_work.swap(0, Ordering::Relaxed);
             let elapsed = start.elapsed().as_secs_f64();
+
+            // Merge in whatever each worker has handed off since the last
+            // report, then drain the slots so the same samples aren't
+            // double-counted next interval.
+            let mut merged = new_latency_histogram();
+            for slot in &latency_hists {
+                let mut h = slot.lock().unwrap();
+                merged.add(&*h).ok();
+                h.reset();
+            }
+
+            let huge_pages = read_resident_hugepages(&args.dev).unwrap_or(0);
+
             println!(
-                "{},{},{},{},{:.2},{:.2},{},{:.2},{}",
+                "{},{},{},{},{:.2},{:.2},{:.2},{},{:.2},{},{},{},{},{},{}",
                 args.dev,
                 args.seq,
                 args.hint,
                 args.threads,
                 elapsed,
                 (work_count * PAGE_SIZE) as f64 / GB as f64,
+                (write_count * PAGE_SIZE) as f64 / GB as f64,
                 shootdowns - last_shootdowns,
                 (io_bytes - last_io_bytes) as f64 / GB as f64,
-                cpu_work_count
+                cpu_work_count,
+                merged.value_at_quantile(0.50),
+                merged.value_at_quantile(0.99),
+                merged.value_at_quantile(0.999),
+                merged.max(),
+                huge_pages
             );
             last_shootdowns = shootdowns;
             last_io_bytes = io_bytes;
@@ -109,52 +440,318 @@ fn main() -> io::Result<()> {
     Ok(())
 }
 
+/// Splits the file into `chunk_size_blocks`-block chunks, shuffles them, and deals
+/// them round-robin into one plan per thread. Shuffling spreads each
+/// thread's chunks across the whole device so regions with different media
+/// behavior (e.g. near vs. far on a spinning disk) are sampled evenly.
+fn build_seq_scan_plans(file_size: u64, threads: usize) -> Vec<Vec<(u64, u64)>> {
+    if threads == 0 {
+        // Nothing to hand work to; match the baseline's behavior of simply
+        // spawning no workers instead of dividing by zero below.
+        return Vec::new();
+    }
+    let nr_blocks = file_size / PAGE_SIZE;
+    let chunk_size_blocks = (nr_blocks / (threads as u64 * CHUNKS_PER_THREAD))
+        .clamp(MIN_CHUNK_BLOCKS, MAX_CHUNK_BLOCKS);
+    let chunk_size = chunk_size_blocks * PAGE_SIZE;
+
+    let mut chunks = Vec::new();
+    let mut start = 0u64;
+    while start < file_size {
+        let end = (start + chunk_size).min(file_size);
+        chunks.push((start, end));
+        start = end;
+    }
+    chunks.shuffle(&mut rand::thread_rng());
+
+    let mut plans = vec![Vec::new(); threads];
+    for (i, chunk) in chunks.iter().enumerate() {
+        plans[i % threads].push(*chunk);
+    }
+    // A small file (or a large --threads count) can leave fewer chunks than
+    // threads, so the round-robin deal above skips some threads entirely.
+    // Wrap the chunk list so every thread gets at least one (possibly
+    // shared) chunk instead of spinning forever on an empty plan.
+    for (t, plan) in plans.iter_mut().enumerate() {
+        if plan.is_empty() {
+            plan.push(chunks[t % chunks.len()]);
+        }
+    }
+    plans
+}
+
+/// An explicit MAP_HUGETLB mapping, bypassing memmap2 since huge pages need
+/// a raw `mmap(2)` call with `MAP_HUGETLB` and an encoded page-size hint.
+struct HugeTlbMap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl HugeTlbMap {
+    fn new(file: &File, len: usize, huge_page_size: u64) -> io::Result<Self> {
+        // MAP_HUGETLB encodes the desired huge page size as `log2(size) <<
+        // MAP_HUGE_SHIFT`; default to 2 MiB pages for anything we don't
+        // recognize.
+        let shift: i32 = match huge_page_size {
+            s if s == 1024 * MB => 30,
+            _ => 21,
+        };
+        let flags = libc::MAP_SHARED | libc::MAP_HUGETLB | (shift << libc::MAP_HUGE_SHIFT);
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(Self {
+                ptr: ptr as *mut u8,
+                len,
+            })
+        }
+    }
+}
+
+impl Deref for HugeTlbMap {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for HugeTlbMap {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
+
+/// A raw pointer into the shared writable mapping. Multiple worker threads
+/// hold copies of this concurrently and store through it without any
+/// synchronization; their offsets can overlap, so concurrent stores to the
+/// same byte may race.
+#[derive(Clone, Copy)]
+struct RawMut(*mut u8);
+
+unsafe impl Send for RawMut {}
+unsafe impl Sync for RawMut {}
+
+#[allow(clippy::too_many_arguments)]
 fn spawn_worker_threads<'scope>(
     s: &'scope thread::Scope<'scope, '_>,
     args: &Args,
-    counts: &'scope AtomicU64,
-    sums: &'scope AtomicU64,
-    seq_scan_pos: &'scope AtomicU64,
-    mmap: &'scope [u8],
+    rw: RwMode,
+    counter_store: &'scope CounterStore,
+    seq_plans: Vec<Vec<(u64, u64)>>,
+    mmap: Option<&'scope [u8]>,
+    mmap_rw_ptr: Option<RawMut>,
+    pread_file: Option<&'scope File>,
+    direct_active: bool,
+    direct_align: u64,
     file_size: u64,
+    latency_hists: &'scope [Mutex<Histogram<u64>>],
 ) {
-    for _ in 0..args.threads {
-        let counts = counts;
-        let sums = sums;
-        let seq_scan_pos = seq_scan_pos;
-        let mmap = mmap;
-        let file_size = file_size;
+    for (thread_idx, plan) in seq_plans.into_iter().enumerate() {
+        let counters = counter_store.sink(thread_idx);
         let seq = args.seq;
+        let method = args.method;
+        let write_ratio = args.write_ratio;
+        let latency_slot = &latency_hists[thread_idx];
 
         s.spawn(move || {
+            let mut pread_buf = [0u8; PAGE_SIZE as usize];
+            let mut direct_buf = AlignedBuf::new(PAGE_SIZE as usize, direct_align as usize);
+            let mut rng = rand::thread_rng();
+            let mut since_msync = 0u64;
+            let mut write_val = 0u8;
+            let mut local_hist = new_latency_histogram();
+            let mut last_flush = Instant::now();
+
+            let mut read_one = |idx: u64| -> u64 {
+                match method {
+                    Method::Mmap => mmap.unwrap()[idx as usize] as u64,
+                    Method::Pread if direct_active => {
+                        let file = pread_file.unwrap();
+                        let aligned = idx - (idx % direct_align);
+                        match file.read_at(&mut direct_buf, aligned) {
+                            // A zero-length read means we ran past the end of
+                            // the device; wrap back to the start rather than
+                            // treating it as an error.
+                            Ok(0) => {
+                                file.read_exact_at(&mut direct_buf, 0)
+                                    .expect("pread failed");
+                            }
+                            Ok(_) => {}
+                            Err(e) => panic!("pread failed: {e}"),
+                        }
+                        direct_buf[0] as u64
+                    }
+                    Method::Pread => {
+                        let file = pread_file.unwrap();
+                        // `idx` is an arbitrary byte offset, so a full
+                        // PAGE_SIZE read starting there can run past EOF; clamp
+                        // to the last full page rather than reading off the
+                        // end. A zero-length read wraps to the start, as on
+                        // the O_DIRECT path.
+                        let last_page_start = file_size.saturating_sub(PAGE_SIZE);
+                        let clamped = idx.min(last_page_start);
+                        match file.read_at(&mut pread_buf, clamped) {
+                            Ok(0) => {
+                                file.read_exact_at(&mut pread_buf, 0).expect("pread failed");
+                            }
+                            Ok(_) => {}
+                            Err(e) => panic!("pread failed: {e}"),
+                        }
+                        pread_buf[0] as u64
+                    }
+                }
+            };
+
+            // Stores a byte into the MAP_SHARED mapping and, every
+            // `MSYNC_EVERY` writes, flushes the page it just dirtied back to
+            // the file so write-back cost shows up in the measurement
+            // instead of being deferred entirely to the kernel's pdflush.
+            let mut write_one = |idx: u64| {
+                let ptr = mmap_rw_ptr.expect("write workload requires a writable mapping");
+                write_val = write_val.wrapping_add(1);
+                unsafe { *ptr.0.add(idx as usize) = write_val };
+
+                since_msync += 1;
+                if since_msync >= MSYNC_EVERY {
+                    since_msync = 0;
+                    let page_addr = (idx / PAGE_SIZE) * PAGE_SIZE;
+                    unsafe {
+                        let addr = ptr.0.add(page_addr as usize) as *mut libc::c_void;
+                        libc::msync(addr, PAGE_SIZE as usize, libc::MS_SYNC);
+                    }
+                }
+            };
+
             if seq != 0 {
+                // Each thread owns a fixed, pre-shuffled list of (start, end)
+                // chunks and just cycles through it — no shared position
+                // counter to contend on.
                 loop {
-                    let pos =
-                        seq_scan_pos.fetch_add(SCAN_BLOCK_SIZE, Ordering::Relaxed) % file_size;
-                    let end = pos + SCAN_BLOCK_SIZE;
-                    let mut j = pos;
-                    while j < end {
-                        let idx = (j % file_size) as usize;
-                        let val = mmap[idx];
-                        sums.fetch_add(val as u64, Ordering::Relaxed);
-                        counts.fetch_add(1, Ordering::Relaxed);
-                        j += PAGE_SIZE;
+                    for &(start, end) in &plan {
+                        let mut idx = start;
+                        while idx < end {
+                            let is_write = match rw {
+                                RwMode::Read => false,
+                                RwMode::Write => true,
+                                RwMode::Mixed => rng.gen_bool(write_ratio.clamp(0.0, 1.0)),
+                            };
+                            let access_start = Instant::now();
+                            let val = if is_write {
+                                write_one(idx);
+                                0
+                            } else {
+                                read_one(idx)
+                            };
+                            local_hist
+                                .record(access_start.elapsed().as_nanos() as u64)
+                                .ok();
+                            counters.record(is_write, val);
+                            idx += PAGE_SIZE;
+
+                            if last_flush.elapsed() >= Duration::from_secs(1) {
+                                let batch =
+                                    std::mem::replace(&mut local_hist, new_latency_histogram());
+                                latency_slot.lock().unwrap().add(&batch).ok();
+                                last_flush = Instant::now();
+                            }
+                        }
                     }
                 }
             } else {
-                let mut rng = rand::thread_rng();
                 loop {
                     let pos = rng.gen_range(0..file_size);
-                    let idx = pos as usize % mmap.len();
-                    let val = mmap[idx];
-                    sums.fetch_add(val as u64, Ordering::Relaxed);
-                    counts.fetch_add(1, Ordering::Relaxed);
+                    let idx = pos % file_size;
+                    let is_write = match rw {
+                        RwMode::Read => false,
+                        RwMode::Write => true,
+                        RwMode::Mixed => rng.gen_bool(write_ratio.clamp(0.0, 1.0)),
+                    };
+                    let access_start = Instant::now();
+                    let val = if is_write {
+                        write_one(idx);
+                        0
+                    } else {
+                        read_one(idx)
+                    };
+                    local_hist
+                        .record(access_start.elapsed().as_nanos() as u64)
+                        .ok();
+                    counters.record(is_write, val);
+
+                    if last_flush.elapsed() >= Duration::from_secs(1) {
+                        let batch = std::mem::replace(&mut local_hist, new_latency_histogram());
+                        latency_slot.lock().unwrap().add(&batch).ok();
+                        last_flush = Instant::now();
+                    }
                 }
             }
         });
     }
 }
 
+/// A heap buffer aligned to `align` bytes, required for O_DIRECT reads which
+/// reject unaligned buffers.
+struct AlignedBuf {
+    ptr: *mut u8,
+    len: usize,
+    layout: Layout,
+}
+
+impl AlignedBuf {
+    fn new(len: usize, align: usize) -> Self {
+        let layout = Layout::from_size_align(len, align).expect("invalid buffer layout");
+        let ptr = unsafe { alloc(layout) };
+        assert!(!ptr.is_null(), "aligned allocation failed");
+        Self { ptr, len, layout }
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr, self.layout) };
+    }
+}
+
+// AlignedBuf owns its allocation exclusively, so it's safe to move to another
+// thread despite the raw pointer.
+unsafe impl Send for AlignedBuf {}
+
+/// Queries the logical block size (in bytes) of the device backing `file` via
+/// `BLKSSZGET`, the alignment O_DIRECT offsets and buffer lengths actually
+/// need to respect. A hardcoded 512 works for the common case but returns
+/// `EINVAL` on 4Kn-formatted devices.
+fn query_logical_block_size(file: &File) -> io::Result<u64> {
+    let mut block_size: libc::c_int = 0;
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), libc::BLKSSZGET, &mut block_size) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(block_size as u64)
+}
+
 fn read_tlb_shootdown_count() -> io::Result<u64> {
     let mut contents = String::new();
     File::open("/proc/interrupts")?.read_to_string(&mut contents)?;
@@ -172,6 +769,47 @@ fn read_tlb_shootdown_count() -> io::Result<u64> {
     Ok(0)
 }
 
+// How many kB of resident huge pages count as one huge page in the report.
+// Matches the default transparent-huge-page size on x86_64; larger pages
+// are still counted in 2 MiB units (a 1 GiB page counts as 512), and a
+// remainder smaller than 2 MiB is dropped by the integer division.
+const HUGE_PAGE_KB: u64 = 2 * MB / KB;
+
+/// Sums `AnonHugePages`/`FilePmdMapped` from `/proc/self/smaps` for the VMA
+/// backing `dev_path`, converted from resident kB to a huge-page count.
+fn read_resident_hugepages(dev_path: &str) -> io::Result<u64> {
+    let mut contents = String::new();
+    File::open("/proc/self/smaps")?.read_to_string(&mut contents)?;
+
+    let mut in_target_region = false;
+    let mut total_kb = 0u64;
+    for line in contents.lines() {
+        let is_vma_header = line
+            .split_whitespace()
+            .next()
+            .map(|first| {
+                first.contains('-') && first.chars().all(|c| c.is_ascii_hexdigit() || c == '-')
+            })
+            .unwrap_or(false);
+        if is_vma_header {
+            in_target_region = line.contains(dev_path);
+            continue;
+        }
+        if !in_target_region {
+            continue;
+        }
+        let field = line
+            .strip_prefix("AnonHugePages:")
+            .or_else(|| line.strip_prefix("FilePmdMapped:"));
+        if let Some(field) = field {
+            if let Ok(kb) = field.trim().trim_end_matches(" kB").trim().parse::<u64>() {
+                total_kb += kb;
+            }
+        }
+    }
+    Ok(total_kb / HUGE_PAGE_KB)
+}
+
 fn read_io_bytes() -> io::Result<u64> {
     let mut contents = String::new();
     File::open("/proc/diskstats")?.read_to_string(&mut contents)?;